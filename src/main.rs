@@ -4,7 +4,7 @@ use std::fmt;
 use std::fs;
 use std::io;
 use std::io::{BufWriter, Write};
-use std::path::{self, PathBuf};
+use std::path::{self, Path, PathBuf};
 use std::process::exit;
 use std::sync::Mutex;
 use std::time::Duration;
@@ -79,6 +79,47 @@ fn parent_process_watchdog() {
     parent_died();
 }
 
+#[cfg(target_os = "macos")]
+fn parent_process_watchdog() {
+    fn parent_died() -> ! {
+        eprintln!("parent process died");
+        exit(1);
+    }
+
+    use rustix::event::kqueue::{kevent, kqueue, Event, EventFilter, EventFlags, ProcessEvents};
+    use rustix::io::Errno;
+    use rustix::process::getppid;
+
+    let Some(ppid) = getppid() else {
+        parent_died();
+    };
+
+    let kq = kqueue().expect("failed to create kqueue");
+
+    if getppid() != Some(ppid) {
+        parent_died();
+    }
+
+    let event = Event::new(
+        EventFilter::Proc {
+            pid: ppid,
+            flags: ProcessEvents::EXIT,
+        },
+        EventFlags::ADD,
+        0,
+    );
+
+    loop {
+        let mut eventlist = Vec::with_capacity(1);
+        match unsafe { kevent(&kq, &[event.clone()], &mut eventlist, None) } {
+            Ok(_) => parent_died(),
+            Err(Errno::INTR) => continue,
+            Err(Errno::SRCH) => parent_died(),
+            Err(e) => panic!("kevent failed: {e:?}"),
+        }
+    }
+}
+
 #[cfg(target_os = "linux")]
 fn enter_efficiency_mode() {
     let param = libc::sched_param { sched_priority: 0 };
@@ -116,6 +157,7 @@ enum EventType {
     Create,
     Change,
     Delete,
+    Move,
 }
 
 impl fmt::Display for EventType {
@@ -124,6 +166,7 @@ impl fmt::Display for EventType {
             EventType::Create => "create",
             EventType::Change => "change",
             EventType::Delete => "delete",
+            EventType::Move => "move",
         }
         .fmt(f)
     }
@@ -136,8 +179,34 @@ struct RegisterRequest {
     ignores: Vec<String>,
     patterns: Vec<String>,
     uid: usize,
+    #[serde(default)]
+    use_gitignore: bool,
+    #[serde(default)]
+    no_default_ignores: bool,
+    #[serde(default = "default_recursive")]
+    recursive: bool,
+    #[serde(default)]
+    debounce_ms: Option<u64>,
 }
 
+fn default_recursive() -> bool {
+    true
+}
+
+const DEFAULT_DEBOUNCE_MS: u64 = 400;
+
+const DEFAULT_IGNORES: &[&str] = &[
+    "**/.git/**",
+    "**/.hg/**",
+    "**/.svn/**",
+    "**/.DS_Store",
+    "*.py[co]",
+    "**/#*#",
+    "**/.#*",
+    "**/.*.sw?",
+    "**/.*.sw?x",
+];
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "lowercase")]
 enum Request {
@@ -152,6 +221,9 @@ struct WatcherConfig {
     ignores: Vec<Pattern>,
     patterns: Vec<Pattern>,
     prefixes: Vec<PathBuf>,
+    use_gitignore: bool,
+    recursive: bool,
+    debounce_ms: u64,
 }
 
 impl WatcherConfig {
@@ -170,7 +242,7 @@ impl WatcherConfig {
             })
         };
 
-        let paths_to_patterns = |paths: &Vec<String>| {
+        let paths_to_patterns = |paths: &Vec<String>| -> Vec<Pattern> {
             make_absolute_paths(paths)
                 .filter_map(|path| {
                     Pattern::new(path.to_string_lossy().as_ref()).map_or_else(
@@ -186,7 +258,12 @@ impl WatcherConfig {
 
         let prefixes: Vec<_> = make_absolute_paths(&req.patterns).collect();
         let patterns = paths_to_patterns(&req.patterns);
-        let ignores = paths_to_patterns(&req.ignores);
+        let mut ignores = paths_to_patterns(&req.ignores);
+        if !req.no_default_ignores {
+            let default_ignores: Vec<String> =
+                DEFAULT_IGNORES.iter().map(|s| s.to_string()).collect();
+            ignores.extend(paths_to_patterns(&default_ignores));
+        }
 
         let events = req.events;
 
@@ -196,17 +273,140 @@ impl WatcherConfig {
             ignores,
             patterns,
             prefixes,
+            use_gitignore: req.use_gitignore,
+            recursive: req.recursive,
+            debounce_ms: req.debounce_ms.unwrap_or(DEFAULT_DEBOUNCE_MS),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct GitignoreRule {
+    pattern: Pattern,
+    descendants: Pattern,
+    negate: bool,
+    dir_only: bool,
+}
+
+impl GitignoreRule {
+    fn matches(&self, path: &Path) -> bool {
+        if self.descendants.matches_path(path) {
+            return true;
         }
+        self.pattern.matches_path(path)
+            && (!self.dir_only || fs::metadata(path).map(|m| m.is_dir()).unwrap_or(true))
     }
 }
 
+fn parse_gitignore_file(path: &Path) -> Vec<GitignoreRule> {
+    let Some(base) = path.parent() else {
+        return Vec::new();
+    };
+    let Ok(contents) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+
+    let mut rules = Vec::new();
+    for mut line in contents.lines() {
+        line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let negate = line.starts_with('!');
+        if negate {
+            line = &line[1..];
+        }
+
+        let dir_only = line.ends_with('/');
+        if dir_only {
+            line = &line[..line.len() - 1];
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let anchored = line.starts_with('/');
+        let rel = line.strip_prefix('/').unwrap_or(line);
+        let glob_str = if anchored || rel.contains('/') {
+            format!("{}/{rel}", base.to_string_lossy())
+        } else {
+            format!("{}/**/{rel}", base.to_string_lossy())
+        };
+
+        let descendants_glob_str = format!("{glob_str}/**");
+        match (Pattern::new(&glob_str), Pattern::new(&descendants_glob_str)) {
+            (Ok(pattern), Ok(descendants)) => rules.push(GitignoreRule {
+                pattern,
+                descendants,
+                negate,
+                dir_only,
+            }),
+            (Err(e), _) | (_, Err(e)) => eprintln!("invalid gitignore pattern {line:?}: {e:?}"),
+        }
+    }
+    rules
+}
+
+fn cached_gitignore_rules(
+    cache: &Mutex<BTreeMap<PathBuf, Vec<GitignoreRule>>>,
+    path: &Path,
+) -> Vec<GitignoreRule> {
+    if let Some(rules) = cache.lock().unwrap().get(path) {
+        return rules.clone();
+    }
+    let rules = parse_gitignore_file(path);
+    cache
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), rules.clone());
+    rules
+}
+
+fn last_gitignore_match(rules: &[GitignoreRule], path: &Path) -> Option<bool> {
+    let mut result = None;
+    for rule in rules {
+        if rule.matches(path) {
+            result = Some(!rule.negate);
+        }
+    }
+    result
+}
+
+fn is_gitignored(
+    cache: &Mutex<BTreeMap<PathBuf, Vec<GitignoreRule>>>,
+    cwd: &Path,
+    path: &Path,
+) -> bool {
+    let mut dir = path.parent();
+    while let Some(d) = dir {
+        let mut rules = cached_gitignore_rules(cache, &d.join(".gitignore"));
+        if d == cwd {
+            let mut exclude_rules =
+                cached_gitignore_rules(cache, &cwd.join(".git").join("info").join("exclude"));
+            exclude_rules.extend(rules);
+            rules = exclude_rules;
+        }
+
+        // Nearest gitignore wins: a matching rule in a closer file decides
+        // the outcome; only when it has no match is the parent consulted.
+        if let Some(ignored) = last_gitignore_match(&rules, path) {
+            return ignored;
+        }
+
+        if d == cwd {
+            break;
+        }
+        dir = d.parent();
+    }
+    false
+}
+
 fn normalize_events(events: &mut Vec<notify::Event>) {
-    use notify::event::{CreateKind, EventAttributes, ModifyKind, RemoveKind, RenameMode};
-    use notify::{Event, EventKind};
+    use notify::event::{CreateKind, ModifyKind, RemoveKind, RenameMode};
+    use notify::EventKind;
 
-    let mut i = 0;
-    while i < events.len() {
-        let event = &mut events[i];
+    for event in events {
         if let EventKind::Modify(ModifyKind::Name(rename)) = event.kind {
             match rename {
                 RenameMode::From => {
@@ -215,27 +415,38 @@ fn normalize_events(events: &mut Vec<notify::Event>) {
                 RenameMode::To => {
                     event.kind = EventKind::Create(CreateKind::Any);
                 }
-                RenameMode::Both => {
-                    assert_eq!(event.paths.len(), 2);
-                    event.kind = EventKind::Remove(RemoveKind::Any);
-                    let dest = event.paths.pop().unwrap();
-                    events.insert(
-                        i + 1,
-                        Event {
-                            kind: EventKind::Modify(ModifyKind::Name(RenameMode::To)),
-                            paths: vec![dest],
-                            attrs: EventAttributes::new(),
-                        },
-                    )
-                }
                 _ => (),
             }
         }
-        i += 1;
     }
 }
 
-fn event_handler(configs: &Mutex<BTreeMap<usize, WatcherConfig>>, events: DebounceEventResult) {
+fn path_is_watched(
+    config: &WatcherConfig,
+    gitignore_cache: &Mutex<BTreeMap<PathBuf, Vec<GitignoreRule>>>,
+    path: &Path,
+) -> bool {
+    let in_scope = config
+        .patterns
+        .iter()
+        .any(|pattern| pattern.matches_path(path))
+        || config
+            .prefixes
+            .iter()
+            .any(|prefix| path.starts_with(prefix));
+    let ignored = config
+        .ignores
+        .iter()
+        .any(|ignore| ignore.matches_path(path))
+        || (config.use_gitignore && is_gitignored(gitignore_cache, &config.cwd, path));
+    in_scope && !ignored
+}
+
+fn event_handler(
+    configs: &Mutex<BTreeMap<usize, WatcherConfig>>,
+    gitignore_cache: &Mutex<BTreeMap<PathBuf, Vec<GitignoreRule>>>,
+    events: DebounceEventResult,
+) {
     let mut events = match events {
         Ok(events) => events.into_iter().map(|event| event.event).collect(),
         Err(errors) => {
@@ -251,6 +462,68 @@ fn event_handler(configs: &Mutex<BTreeMap<usize, WatcherConfig>>, events: Deboun
     let mut stdout = BufWriter::new(io::stdout().lock());
     let mut written = false;
     for event in events {
+        for path in event.paths.iter() {
+            if path.file_name().is_some_and(|n| n == ".gitignore")
+                || path.ends_with(Path::new(".git").join("info").join("exclude"))
+            {
+                gitignore_cache.lock().unwrap().remove(path.as_path());
+            }
+        }
+
+        if matches!(
+            event.kind,
+            notify::EventKind::Modify(notify::event::ModifyKind::Name(
+                notify::event::RenameMode::Both
+            ))
+        ) {
+            let [old_path, new_path] = event.paths.as_slice() else {
+                continue;
+            };
+
+            for (uid, config) in configs.lock().unwrap().iter() {
+                let old_watched = path_is_watched(config, gitignore_cache, old_path);
+                let new_watched = path_is_watched(config, gitignore_cache, new_path);
+
+                let mut emit_half = |event_type: EventType, path: &Path| {
+                    if !config.events.contains(&event_type) {
+                        return;
+                    }
+                    let Ok(rel) = path.strip_prefix(&config.cwd) else {
+                        return;
+                    };
+                    writeln!(stdout, "{}:{}:{}", uid, event_type, rel.to_string_lossy()).unwrap();
+                    written = true;
+                };
+
+                if config.events.contains(&EventType::Move) && old_watched && new_watched {
+                    let (Ok(old_rel), Ok(new_rel)) = (
+                        old_path.strip_prefix(&config.cwd),
+                        new_path.strip_prefix(&config.cwd),
+                    ) else {
+                        continue;
+                    };
+                    writeln!(
+                        stdout,
+                        "{}:{}:{}\t{}",
+                        uid,
+                        EventType::Move,
+                        old_rel.to_string_lossy(),
+                        new_rel.to_string_lossy()
+                    )
+                    .unwrap();
+                    written = true;
+                } else {
+                    if old_watched {
+                        emit_half(EventType::Delete, old_path);
+                    }
+                    if new_watched {
+                        emit_half(EventType::Create, new_path);
+                    }
+                }
+            }
+            continue;
+        }
+
         let event_type = match event.kind {
             notify::EventKind::Create(_) => EventType::Create,
             notify::EventKind::Modify(_) => EventType::Change,
@@ -264,19 +537,7 @@ fn event_handler(configs: &Mutex<BTreeMap<usize, WatcherConfig>>, events: Deboun
             }
 
             for path in event.paths.iter() {
-                if config
-                    .patterns
-                    .iter()
-                    .all(|pattern| !pattern.matches_path(&path))
-                    && config
-                        .prefixes
-                        .iter()
-                        .all(|prefix| !path.starts_with(prefix))
-                    || config
-                        .ignores
-                        .iter()
-                        .any(|ignore| ignore.matches_path(&path))
-                {
+                if !path_is_watched(config, gitignore_cache, path) {
                     continue;
                 }
 
@@ -302,21 +563,14 @@ fn main() {
     compile_error!("unsupported platform");
 
     #[cfg(any(target_os = "linux", windows))]
-    {
-        enter_efficiency_mode();
-        drop(std::thread::spawn(parent_process_watchdog));
-    }
+    enter_efficiency_mode();
+
+    drop(std::thread::spawn(parent_process_watchdog));
 
     let configs = Box::leak(Box::new(Mutex::new(BTreeMap::new())));
-    let mut watching_path = BTreeMap::new();
-    let mut watcher = notify_debouncer_full::new_debouncer_opt(
-        Duration::from_millis(400),
-        None,
-        |events| event_handler(configs, events),
-        notify_debouncer_full::NoCache,
-        notify::Config::default(),
-    )
-    .expect("failed to create watcher");
+    let gitignore_cache = Box::leak(Box::new(Mutex::new(BTreeMap::new())));
+
+    let mut debouncers = BTreeMap::new();
 
     for input in io::stdin().lines() {
         let input = input.expect("failed to read from stdin");
@@ -327,13 +581,29 @@ fn main() {
                 Entry::Occupied(_) => eprintln!("watcher with ID {} already exists", req.uid),
                 Entry::Vacant(entry) => {
                     let config = WatcherConfig::from_request(req);
+                    let (watcher, watching_path) = debouncers
+                        .entry((config.debounce_ms, config.recursive))
+                        .or_insert_with(|| {
+                            let watcher = notify_debouncer_full::new_debouncer_opt(
+                                Duration::from_millis(config.debounce_ms),
+                                None,
+                                |events| event_handler(configs, gitignore_cache, events),
+                                notify_debouncer_full::FileIdMap::new(),
+                                notify::Config::default(),
+                            )
+                            .expect("failed to create watcher");
+                            (watcher, BTreeMap::new())
+                        });
+
                     if let Some(count) = watching_path.get_mut(&config.cwd) {
                         *count += 1;
                     } else {
-                        if let Err(e) = watcher
-                            .watcher()
-                            .watch(&config.cwd, notify::RecursiveMode::Recursive)
-                        {
+                        let mode = if config.recursive {
+                            notify::RecursiveMode::Recursive
+                        } else {
+                            notify::RecursiveMode::NonRecursive
+                        };
+                        if let Err(e) = watcher.watcher().watch(&config.cwd, mode) {
                             eprintln!("failed to watch on path: {e:?}");
                             continue;
                         }
@@ -344,6 +614,9 @@ fn main() {
             },
             Request::Unregister(uid) => {
                 if let Some(config) = configs.lock().unwrap().remove(&uid) {
+                    let (watcher, watching_path) = debouncers
+                        .get_mut(&(config.debounce_ms, config.recursive))
+                        .unwrap();
                     let count = watching_path.get_mut(&config.cwd).unwrap();
                     *count -= 1;
                     if *count == 0 {